@@ -1,98 +1,1603 @@
-use std::net::{TcpListener, TcpStream};
+use std::net::{IpAddr, Ipv6Addr, SocketAddr, TcpListener, TcpStream};
 use std::env;
-#[macro_use]
-extern crate log;
+use tracing::{debug, error, info, info_span, warn, field, Span};
+use tracing_subscriber::EnvFilter;
 use std::{thread, sync::{Arc, Mutex, mpsc}}; 
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::{LazyLock, OnceLock};
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::collections::{HashMap, VecDeque};
+use std::str::FromStr;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 use core::str;
-use std::io::{Read, Write};
-use std::fs::File;
+use std::io::{ErrorKind, IsTerminal, Read, Write};
+use std::fs;
+use std::borrow::Cow;
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
+use std::path::{Path, PathBuf};
+#[cfg(feature = "sse-gzip")]
+use flate2::{write::GzEncoder, Compression};
 
-// クライアントにメッセージを送るための送信元のリスト
-type Senders = Arc<Mutex<Vec<mpsc::Sender<String>>>>;
+// SSE接続ごとに振る接続ID
+static NEXT_CLIENT_ID: AtomicUsize = AtomicUsize::new(0);
+
+// ログでリクエストを見分けるための連番
+static NEXT_REQUEST_ID: AtomicU64 = AtomicU64::new(1);
+
+// サーバーの起動時刻 (稼働時間の計算用)
+static STARTED_AT: OnceLock<Instant> = OnceLock::new();
+
+// リクエストを読み込み中、またはワーカーのキューが空くのを待っている接続の数
+static READING: AtomicUsize = AtomicUsize::new(0);
+
+// 専用スレッドで処理中の長時間接続 (SSE・ロングポーリング) の数
+static ACTIVE_STREAMS: AtomicUsize = AtomicUsize::new(0);
+
+// イベントの種類
+#[derive(Clone, Copy)]
+enum EventKind {
+    // POST /send で投稿されたチャット
+    Chat,
+    // 管理者から全員へのお知らせ
+    Announcement,
+}
+
+impl EventKind {
+    fn as_str(self) -> &'static str {
+        match self {
+            EventKind::Chat => "chat",
+            EventKind::Announcement => "announcement",
+        }
+    }
+}
+
+// 連番のIDつきでクライアントへ流すイベント
+#[derive(Clone)]
+struct ChatEvent {
+    id: u64,
+    kind: EventKind,
+    data: String,
+    // 受信時刻 (UNIX秒)
+    sent_at: u64,
+}
+
+impl ChatEvent {
+    // クライアントへ送るメッセージ本体 {"id":..,"kind":"chat","sent_at":..,"text":".."}
+    fn to_json(&self) -> String {
+        format!(
+            r#"{{"id":{},"kind":"{}","sent_at":{},"text":{}}}"#,
+            self.id,
+            self.kind.as_str(),
+            self.sent_at,
+            json_string(&self.data)
+        )
+    }
+}
+
+// 1つの接続への送信口
+// キューは有限で、溜まりすぎた遅いクライアントは切断する (再接続時に Last-Event-ID で再送される)
+struct Subscriber {
+    client_id: usize,
+    tx: mpsc::SyncSender<ChatEvent>,
+    // まだクライアントへ書き出していないイベントの数
+    depth: Arc<AtomicUsize>,
+}
+
+// 接続側で持つ受信口
+struct Subscription {
+    client_id: usize,
+    rx: mpsc::Receiver<ChatEvent>,
+    depth: Arc<AtomicUsize>,
+}
+
+impl Subscription {
+    fn recv_timeout(&self, timeout: Duration) -> Result<ChatEvent, mpsc::RecvTimeoutError> {
+        let event = self.rx.recv_timeout(timeout)?;
+        self.depth.fetch_sub(1, Ordering::SeqCst);
+        Ok(event)
+    }
+
+    fn try_iter(&self) -> impl Iterator<Item = ChatEvent> + '_ {
+        self.rx.try_iter().inspect(|_| {
+            self.depth.fetch_sub(1, Ordering::SeqCst);
+        })
+    }
+}
+
+// 送信元のリストと再送用の履歴をまとめて持つ
+// 購読開始とブロードキャストを同じロックで行うことで、取りこぼしや重複を防ぐ
+struct ChatHub {
+    next_event_id: u64,
+    history: VecDeque<ChatEvent>,
+    history_limit: usize,
+    // 新規接続時に送る直近のイベント数
+    attach_history: usize,
+    // 1接続あたりのキューの上限
+    queue_limit: usize,
+    // クライアントにメッセージを送るための送信元のリスト
+    senders: Vec<Subscriber>,
+    // キューあふれで切断したクライアントの累計
+    evicted: u64,
+}
+
+type SharedHub = Arc<Mutex<ChatHub>>;
+
+impl ChatHub {
+    fn new(config: &Config) -> ChatHub {
+        ChatHub {
+            next_event_id: 1,
+            history: VecDeque::with_capacity(config.replay_buffer),
+            history_limit: config.replay_buffer,
+            attach_history: config.attach_history,
+            queue_limit: config.client_queue,
+            senders: Vec::new(),
+            evicted: 0,
+        }
+    }
+
+    // 新しい接続を登録し、最初に送るべきイベントを返す
+    // 再接続なら last_event_id より後の取りこぼし分、初回接続なら直近の数件
+    fn subscribe(&mut self, last_event_id: Option<u64>) -> (Subscription, Vec<ChatEvent>) {
+        let (tx, rx) = mpsc::sync_channel(self.queue_limit);
+        let client_id = NEXT_CLIENT_ID.fetch_add(1, Ordering::Relaxed);
+        let depth = Arc::new(AtomicUsize::new(0));
+        self.senders.push(Subscriber { client_id, tx, depth: Arc::clone(&depth) });
+        let missed = match last_event_id {
+            Some(last) => self.history.iter().filter(|event| event.id > last).cloned().collect(),
+            None => {
+                let skip = self.history.len().saturating_sub(self.attach_history);
+                self.history.iter().skip(skip).cloned().collect()
+            }
+        };
+        (Subscription { client_id, rx, depth }, missed)
+    }
+
+    // ポーリングするクライアント向けに、since より後の履歴を新しい方から最大 limit 件取り出す
+    // JSON にするのはロックを放してから呼び出し側で行う
+    fn history_since(&self, since: u64, limit: usize) -> Vec<ChatEvent> {
+        // 履歴は ID の昇順なので、新しい方から数えて取り出せばよい
+        let newer = self.history.iter().rev().take_while(|event| event.id > since).count();
+        self.history.iter().skip(self.history.len() - newer.min(limit)).cloned().collect()
+    }
+
+    fn history_len(&self) -> usize {
+        self.history.len()
+    }
+
+    // ヘルスチェック向けにキューの状態を集計した値だけを JSON にする
+    fn queue_summary_json(&self) -> String {
+        let max_depth = self.senders.iter().map(|sub| sub.depth.load(Ordering::SeqCst)).max().unwrap_or(0);
+        format!(r#""queue_limit":{},"evicted":{},"max_queue_depth":{}"#, self.queue_limit, self.evicted, max_depth)
+    }
+
+    // 管理 API 向けに接続ごとのキューの深さを JSON にする
+    fn queue_stats_json(&self) -> String {
+        let queues: Vec<String> = self
+            .senders
+            .iter()
+            .map(|sub| format!(r#"{{"client":{},"depth":{}}}"#, sub.client_id, sub.depth.load(Ordering::SeqCst)))
+            .collect();
+        format!(r#""queue_limit":{},"evicted":{},"queues":[{}]"#, self.queue_limit, self.evicted, queues.join(","))
+    }
+
+    fn unsubscribe(&mut self, client_id: usize) {
+        self.senders.retain(|sub| sub.client_id != client_id);
+    }
+
+    // 履歴に積んでから、生きている全クライアントへ送信
+    // 切断済みのクライアントと、キューがいっぱいの遅いクライアントは外す
+    fn broadcast(&mut self, kind: EventKind, data: &str) {
+        let sent_at = SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |since| since.as_secs());
+        let event = ChatEvent { id: self.next_event_id, kind, data: data.to_string(), sent_at };
+        self.next_event_id += 1;
+        if self.history.len() == self.history_limit {
+            self.history.pop_front();
+        }
+        if self.history_limit > 0 {
+            self.history.push_back(event.clone());
+        }
+        let mut evicted = 0;
+        self.senders.retain(|sub| {
+            sub.depth.fetch_add(1, Ordering::SeqCst);
+            match sub.tx.try_send(event.clone()) {
+                Ok(()) => true,
+                Err(error) => {
+                    sub.depth.fetch_sub(1, Ordering::SeqCst);
+                    if let mpsc::TrySendError::Full(_) = error {
+                        warn!(client = sub.client_id, "evicting slow SSE client");
+                        evicted += 1;
+                    }
+                    false
+                }
+            }
+        });
+        self.evicted += evicted;
+    }
+}
+
+// バイナリに埋め込んだデフォルトのフロントエンド
+#[cfg(feature = "embed-webroot")]
+const EMBEDDED_INDEX: &[u8] = include_bytes!("index.html");
+
+// レート制限をかけるエンドポイントの種類
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+enum RateClass {
+    // POST /send
+    Send,
+    // GET /events, GET /poll, GET /history
+    Stream,
+}
+
+// トークンバケットの設定 (per_sec が 0 なら制限しない)
+struct RateLimit {
+    per_sec: f64,
+    burst: f64,
+}
+
+impl RateLimit {
+    // {prefix}_RATE (1秒あたりの回数) と {prefix}_BURST (まとめて許す回数) を読む
+    fn from_env(prefix: &str, per_sec: f64, burst: f64) -> RateLimit {
+        RateLimit {
+            per_sec: env_or(&format!("{}_RATE", prefix), per_sec).max(0.0),
+            burst: env_or(&format!("{}_BURST", prefix), burst).max(1.0),
+        }
+    }
+}
+
+struct Bucket {
+    tokens: f64,
+    updated: Instant,
+}
+
+// クライアントIPとエンドポイントの種類ごとのトークンバケット
+struct RateTable {
+    buckets: HashMap<(RateClass, IpAddr), Bucket>,
+    // 満タンに戻ったバケットを最後に捨てた時刻
+    pruned: Instant,
+}
+
+static RATE_BUCKETS: LazyLock<Mutex<RateTable>> =
+    LazyLock::new(|| Mutex::new(RateTable { buckets: HashMap::new(), pruned: Instant::now() }));
+
+// 満タンに戻ったバケットを捨てる間隔
+const RATE_PRUNE_INTERVAL: Duration = Duration::from_secs(60);
+
+// クライアントIPを読み取る転送ヘッダー
+// プロキシが上書き・追記するヘッダーだけを読み、クライアントが勝手に付けた他のヘッダーは無視する
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum ForwardedHeader {
+    XForwardedFor,
+    Forwarded,
+    XRealIp,
+}
+
+impl ForwardedHeader {
+    fn name(self) -> &'static str {
+        match self {
+            ForwardedHeader::XForwardedFor => "X-Forwarded-For",
+            ForwardedHeader::Forwarded => "Forwarded",
+            ForwardedHeader::XRealIp => "X-Real-IP",
+        }
+    }
+}
+
+impl FromStr for ForwardedHeader {
+    type Err = ();
+
+    fn from_str(value: &str) -> Result<ForwardedHeader, ()> {
+        match value.to_ascii_lowercase().as_str() {
+            "x-forwarded-for" => Ok(ForwardedHeader::XForwardedFor),
+            "forwarded" => Ok(ForwardedHeader::Forwarded),
+            "x-real-ip" => Ok(ForwardedHeader::XRealIp),
+            _ => Err(()),
+        }
+    }
+}
+
+// 環境変数から読み込むサーバー設定
+struct Config {
+    // CHAT_WEBROOT: 静的ファイルを置いたディレクトリ (未設定なら埋め込み版を使う)
+    webroot: Option<PathBuf>,
+    // CHAT_BASE_PATH: リバースプロキシ配下で公開するときのパスの接頭辞 (例: /wordwolf)
+    base_path: String,
+    // CHAT_TRUSTED_PROXIES: 転送ヘッダーを信用するプロキシのIP (カンマ区切り)
+    trusted_proxies: Vec<IpAddr>,
+    // CHAT_FORWARDED_HEADER: 信用するプロキシが付けるヘッダー (x-forwarded-for | forwarded | x-real-ip)
+    forwarded_header: ForwardedHeader,
+    // CHAT_PING_INTERVAL: 無通信時に SSE の ping コメントを送る間隔 (秒)
+    ping_interval: Duration,
+    // CHAT_REPLAY_BUFFER: 再接続時の再送用に保持するイベント数
+    replay_buffer: usize,
+    // CHAT_ATTACH_HISTORY: 新規接続時にまとめて送る直近のメッセージ数
+    attach_history: usize,
+    // CHAT_CLIENT_QUEUE: 接続ごとに溜めておける未送信イベントの上限
+    client_queue: usize,
+    // CHAT_POLL_TIMEOUT: ロングポーリングで新着を待つ最大時間 (秒)
+    poll_timeout: Duration,
+    // CHAT_WORKERS (またはコマンドラインの2つ目の引数): 通常のリクエストを処理するワーカー数
+    workers: usize,
+    // CHAT_MAX_STREAMS: 同時に張れる SSE・ロングポーリング接続の上限
+    max_streams: usize,
+    // CHAT_MAX_PENDING: リクエストを読み込み中・ワーカー待ちの接続の上限
+    max_pending: usize,
+    // CHAT_SEND_RATE / CHAT_SEND_BURST: POST /send のレート制限
+    send_limit: RateLimit,
+    // CHAT_STREAM_RATE / CHAT_STREAM_BURST: SSE・ロングポーリング接続のレート制限
+    stream_limit: RateLimit,
+    // CHAT_READ_TIMEOUT: リクエスト全体を読み終える・応答を書き終えるまでの制限時間 (秒)
+    read_timeout: Duration,
+    // CHAT_MAX_HEADER_BYTES: リクエストラインとヘッダーの最大サイズ
+    max_header_bytes: usize,
+    // CHAT_MAX_BODY_BYTES: 本文の最大サイズ
+    max_body_bytes: usize,
+    // CHAT_ADMIN_TOKEN: /admin に必要な Bearer トークン (未設定なら /admin は無効)
+    admin_token: Option<String>,
+}
+
+impl Config {
+    fn rate_limit(&self, class: RateClass) -> &RateLimit {
+        match class {
+            RateClass::Send => &self.send_limit,
+            RateClass::Stream => &self.stream_limit,
+        }
+    }
+
+    fn from_env() -> Config {
+        Config {
+            webroot: env::var_os("CHAT_WEBROOT").map(PathBuf::from),
+            base_path: normalize_base_path(&env::var("CHAT_BASE_PATH").unwrap_or_default()),
+            trusted_proxies: env::var("CHAT_TRUSTED_PROXIES")
+                .unwrap_or_default()
+                .split(',')
+                .filter_map(|ip| ip.trim().parse().ok())
+                .collect(),
+            forwarded_header: env_or("CHAT_FORWARDED_HEADER", ForwardedHeader::XForwardedFor),
+            ping_interval: Duration::from_secs(env_or("CHAT_PING_INTERVAL", 15u64).max(1)),
+            replay_buffer: env_or("CHAT_REPLAY_BUFFER", 100),
+            attach_history: env_or("CHAT_ATTACH_HISTORY", 20),
+            client_queue: env_or("CHAT_CLIENT_QUEUE", 64usize).max(1),
+            poll_timeout: Duration::from_secs(env_or("CHAT_POLL_TIMEOUT", 25)),
+            workers: env_or("CHAT_WORKERS", 8usize).max(1),
+            max_streams: env_or("CHAT_MAX_STREAMS", 1000),
+            max_pending: env_or("CHAT_MAX_PENDING", 256),
+            send_limit: RateLimit::from_env("CHAT_SEND", 2.0, 10.0),
+            stream_limit: RateLimit::from_env("CHAT_STREAM", 1.0, 20.0),
+            read_timeout: Duration::from_secs(env_or("CHAT_READ_TIMEOUT", 10u64).max(1)),
+            max_header_bytes: env_or("CHAT_MAX_HEADER_BYTES", 8 * 1024),
+            max_body_bytes: env_or("CHAT_MAX_BODY_BYTES", 16 * 1024),
+            admin_token: env::var("CHAT_ADMIN_TOKEN").ok().filter(|token| !token.is_empty()),
+        }
+    }
+}
+
+// 数値の環境変数を読む (未設定や不正な値ならデフォルト)
+fn env_or<T: FromStr>(name: &str, default: T) -> T {
+    env::var(name).ok().and_then(|value| value.trim().parse().ok()).unwrap_or(default)
+}
+
+// "wordwolf/" のような指定を "/wordwolf" に揃える (未指定なら空文字)
+fn normalize_base_path(raw: &str) -> String {
+    let trimmed = raw.trim_matches('/');
+    if trimmed.is_empty() {
+        String::new()
+    } else {
+        format!("/{}", trimmed)
+    }
+}
 
 fn main() {
-    env::set_var("RUST_LOG", "debug");
-    env_logger::init();
+    STARTED_AT.get_or_init(Instant::now);
+    // ログレベルは RUST_LOG で指定する (未指定なら info)
+    tracing_subscriber::fmt()
+        .with_env_filter(EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new("info")))
+        .with_writer(std::io::stderr)
+        // ファイルや docker logs へ流すときはエスケープシーケンスを付けない
+        .with_ansi(std::io::stderr().is_terminal())
+        .init();
     let args: Vec<String> = env::args().collect();
-    if args.len() != 2 {
-        error!("Please enter [addr:port]");
+    if args.len() != 2 && args.len() != 3 {
+        error!("Please enter [addr:port] [workers]");
         std::process::exit(1);
     }
 
-    // SSE接続中のクライアントリスト
-    let senders: Senders = Arc::new(Mutex::new(Vec::new()));
+    let mut config = Config::from_env();
+    if let Some(workers) = args.get(2) {
+        match workers.parse::<usize>() {
+            Ok(workers) if workers > 0 => config.workers = workers,
+            _ => {
+                error!("workers must be a positive number: {}", workers);
+                std::process::exit(1);
+            }
+        }
+    }
+    let config = Arc::new(config);
+    // SSE接続中のクライアントとイベント履歴
+    let hub: SharedHub = Arc::new(Mutex::new(ChatHub::new(&config)));
 
     let _address: &str = &args[1];
     let listener = TcpListener::bind(_address).unwrap();
-    info!("Server listening on {}", _address);
+    info!("Server listening on {} with {} workers", _address, config.workers);
 
-    loop {
-        let (stream, _) = listener.accept().unwrap();
-        let senders_clone = Arc::clone(&senders);
+    // 受け付けたソケットは読み込み用のスレッドでリクエストを読み切ってから、固定数のワーカーへ配る
+    // 何も送ってこない接続や遅い接続がワーカーを塞がないようにするため
+    // キューがいっぱいの間は読み込み用のスレッドが待ち、その数は max_pending で抑える
+    let (job_tx, job_rx) = mpsc::sync_channel::<Job>(config.workers * 4);
+    let job_rx = Arc::new(Mutex::new(job_rx));
+    for _ in 0..config.workers {
+        spawn_worker(Arc::clone(&job_rx), Arc::clone(&hub), Arc::clone(&config));
+    }
 
-        thread::spawn(move || {
-            handler(stream, senders_clone).unwrap_or_else(|error| error!("{:?}", error));
-        });
+    for stream in listener.incoming() {
+        match stream {
+            Ok(stream) => spawn_reader(stream, job_tx.clone(), Arc::clone(&config)),
+            Err(error) => error!("accept failed: {}", error),
+        }
     }
 }
 
-fn handler(mut stream: TcpStream, senders: Senders) -> Result<(), failure::Error> {
-    let mut buffer = [0u8; 1024];
-    let nbytes = stream.read(&mut buffer)?;
-    if nbytes == 0 { return Ok(()); }
-    let request = str::from_utf8(&buffer[..nbytes])?;
+// 読み込み済みのリクエスト (ワーカーが処理する単位)
+struct Job {
+    stream: TcpStream,
+    request: String,
+    // アクセスログ用の span と受け付けた時刻
+    span: Span,
+    started: Instant,
+}
 
-    if request.contains("GET /events") {
-        // --- SSE 接続の開始 ---
-        let (tx, rx) = mpsc::channel();
-        {
-            senders.lock().unwrap().push(tx);
+// リクエストを読み切るまでは専用スレッドで待ち、読めたらワーカーのキューへ積む
+// 読み込み中・キュー待ちの接続が上限を超えたら 503 を返して断る
+fn spawn_reader(mut stream: TcpStream, job_tx: mpsc::SyncSender<Job>, config: Arc<Config>) {
+    // リクエストごとにIDつきの span を張り、ステータスと処理時間をアクセスログに残す
+    let span = info_span!(
+        "request",
+        id = NEXT_REQUEST_ID.fetch_add(1, Ordering::Relaxed),
+        method = field::Empty,
+        path = field::Empty,
+        client = field::Empty,
+        status = field::Empty,
+    );
+    let started = Instant::now();
+    if READING.fetch_add(1, Ordering::SeqCst) >= config.max_pending {
+        READING.fetch_sub(1, Ordering::SeqCst);
+        let _enter = span.enter();
+        send_status(&mut stream, "503 Service Unavailable", "Too many connections")
+            .unwrap_or_else(|error| error!("{:?}", error));
+        info!(latency_ms = started.elapsed().as_millis() as u64, "request completed");
+        return;
+    }
+    thread::spawn(move || {
+        let request = span.in_scope(|| {
+            let request = read_request(&mut stream, &config).unwrap_or_else(|error| {
+                error!("{:?}", error);
+                None
+            });
+            if request.is_none() {
+                info!(latency_ms = started.elapsed().as_millis() as u64, "request completed");
+            }
+            request
+        });
+        // キューに入るまでは枠を持ったままにして、待っているスレッドも上限に数える
+        if let Some(request) = request {
+            let job = Job { stream, request, span: span.clone(), started };
+            if job_tx.send(job).is_err() {
+                span.in_scope(|| error!("worker pool is gone"));
+            }
+        }
+        READING.fetch_sub(1, Ordering::SeqCst);
+    });
+}
+
+// キューからリクエストを1件ずつ取り出して処理する
+// 処理中に panic してもワーカーは減らさず、次のリクエストへ進む
+fn spawn_worker(job_rx: Arc<Mutex<mpsc::Receiver<Job>>>, hub: SharedHub, config: Arc<Config>) {
+    thread::spawn(move || loop {
+        let job = match job_rx.lock().unwrap().recv() {
+            Ok(job) => job,
+            Err(_) => break,
+        };
+        let span = job.span.clone();
+        let handled = panic::catch_unwind(AssertUnwindSafe(|| handle_job(job, Arc::clone(&hub), &config)));
+        if handled.is_err() {
+            span.in_scope(|| error!("worker panicked while handling the request"));
         }
+    });
+}
 
-        let header = "HTTP/1.1 200 OK\r\n\
-                      Content-Type: text/event-stream\r\n\
-                      Cache-Control: no-cache\r\n\
-                      Connection: keep-alive\r\n\
-                      Access-Control-Allow-Origin: *\r\n\r\n";
-        stream.write_all(header.as_bytes())?;
+fn handle_job(job: Job, hub: SharedHub, config: &Arc<Config>) {
+    let Job { stream, request, span, started } = job;
+    let _enter = span.enter();
+    handler(stream, &request, hub, config).unwrap_or_else(|error| error!("{:?}", error));
+    info!(latency_ms = started.elapsed().as_millis() as u64, "request completed");
+}
 
-        // チャンネルからメッセージが来るのを待機し、ストリームに流し続ける
-        while let Ok(msg) = rx.recv() {
-            // SSEのフォーマットは "data: メッセージ\n\n"
-            let fmt_msg = format!("data: {}\n\n", msg);
-            if let Err(_) = stream.write_all(fmt_msg.as_bytes()) {
-                break; // クライアントが切断したらループを抜ける
+fn handler(mut stream: TcpStream, request: &str, hub: SharedHub, config: &Arc<Config>) -> Result<(), failure::Error> {
+
+    // リクエストラインからメソッドとパスを取り出す ("メソッド 対象 バージョン" の3つでなければ 400)
+    let request_line: Vec<&str> = request.lines().next().unwrap_or("").split_whitespace().collect();
+    let (method, target) = match request_line[..] {
+        [method, target, version] if version.starts_with("HTTP/") => (method, target),
+        _ => {
+            send_status(&mut stream, "400 Bad Request", "Malformed request line")?;
+            return Ok(());
+        }
+    };
+    let (raw_path, query) = target.split_once('?').unwrap_or((target, ""));
+    let peer = stream.peer_addr()?.ip();
+    let client = client_ip(peer, request, &config.trusted_proxies, config.forwarded_header);
+    let proto = forwarded_proto(peer, request, &config.trusted_proxies);
+    Span::current()
+        .record("method", method)
+        .record("path", raw_path)
+        .record("client", field::display(client));
+    debug!(proto, "request received");
+
+    // プロキシが接頭辞を付けたまま転送してきた場合は取り除く
+    let path = match raw_path.strip_prefix(config.base_path.as_str()) {
+        Some("") => {
+            // 相対URLを正しく解決させるため末尾のスラッシュ付きへ誘導する (クエリは引き継ぐ)
+            // 誘導先は index.html なので、メソッドの確認も先にしておく
+            if method != "GET" && method != "HEAD" {
+                send_method_not_allowed(&mut stream, "GET, HEAD")?;
+                return Ok(());
             }
-            stream.flush()?;
+            let location = match query {
+                "" => format!("{}/", config.base_path),
+                query => format!("{}/?{}", config.base_path, query),
+            };
+            send_redirect(&mut stream, &location)?;
+            return Ok(());
         }
-        debug!("SSE Connection closed.");
+        Some(stripped) if stripped.starts_with('/') => stripped,
+        _ => raw_path,
+    };
 
-    } else if request.contains("POST /send") {
+    // パスごとに受け付けるメソッド (存在するパスでメソッドが違えば 405、存在しないパスは 404)
+    // それ以外のパスは静的ファイルとして GET と HEAD だけを受け付ける
+    let allowed: &[&str] = match path {
+        "/send" | "/admin/announce" => &["POST"],
+        "/events" | "/poll" => &["GET"],
+        _ => &["GET", "HEAD"],
+    };
+    if !allowed.contains(&method) {
+        let known = matches!(path, "/send" | "/admin/announce" | "/events" | "/poll" | "/admin/connections" | "/health" | "/history")
+            || load_asset(config, asset_path(path))?.is_some();
+        if known {
+            send_method_not_allowed(&mut stream, &allowed.join(", "))?;
+        } else {
+            send_status(&mut stream, "404 Not Found", "Not found")?;
+        }
+        return Ok(());
+    }
+
+    // エンドポイントの種類ごとにクライアントIP単位でレート制限する
+    let rate_class = match (method, path) {
+        ("POST", "/send") => Some(RateClass::Send),
+        ("GET", "/events") | ("GET", "/poll") | (_, "/history") => Some(RateClass::Stream),
+        _ => None,
+    };
+    if let Some(class) = rate_class {
+        if let Err(retry_after) = take_token(class, client, config) {
+            send_too_many_requests(&mut stream, retry_after)?;
+            return Ok(());
+        }
+    }
+
+    if path.starts_with("/admin/") && method == "HEAD" {
+        // --- 運用者向けの管理 API (HEAD はヘッダーだけ) ---
+        serve_admin(&mut HeadersOnly::new(&mut stream), &hub, config, request, path)?;
+
+    } else if path.starts_with("/admin/") {
+        serve_admin(&mut stream, &hub, config, request, path)?;
+
+    } else if method == "GET" && path == "/events" {
+        // --- SSE 接続の開始 (長時間つながるので専用スレッドへ渡す) ---
+        let (request, shared) = (request.to_string(), Arc::clone(config));
+        spawn_long_lived(stream, config, move |stream| serve_events(stream, hub, &shared, &request))?;
+
+    } else if method == "POST" && path == "/send" {
         // --- メッセージの送信 (ブロードキャスト) ---
-        let body = request.split("\r\n\r\n").last().unwrap_or("");
+        let body = request.split_once("\r\n\r\n").map_or("", |(_, body)| body);
         if !body.is_empty() {
-            let mut s = senders.lock().unwrap();
-            // 生きている全クライアントへ送信（切断済みは削除）
-            s.retain(|tx| tx.send(body.to_string()).is_ok());
+            hub.lock().unwrap().broadcast(EventKind::Chat, body);
         }
         send_response(&mut stream, "OK", "text/plain")?;
 
+    } else if method == "GET" && path == "/poll" {
+        // --- ロングポーリング (SSE が使えない環境向け) ---
+        let since = query_param(query, "since").and_then(|id| id.parse().ok()).unwrap_or(0);
+        let shared = Arc::clone(config);
+        spawn_long_lived(stream, config, move |stream| serve_poll(stream, hub, &shared, since))?;
+
+    } else if method == "HEAD" {
+        // HEAD は GET と同じ応答からヘッダーだけを返す
+        serve_get(&mut HeadersOnly::new(&mut stream), &hub, config, request, path, query)?;
+
+    } else {
+        serve_get(&mut stream, &hub, config, request, path, query)?;
+    }
+    Ok(())
+}
+
+// 管理 API (Authorization: Bearer <CHAT_ADMIN_TOKEN> が必要)
+fn serve_admin<W: Write>(stream: &mut W, hub: &SharedHub, config: &Config, request: &str, path: &str) -> std::io::Result<()> {
+    let token = match &config.admin_token {
+        Some(token) => token,
+        None => return send_status(stream, "404 Not Found", "Not found"),
+    };
+    let presented = header(request, "Authorization").and_then(|value| value.strip_prefix("Bearer "));
+    if !presented.is_some_and(|presented| constant_time_eq(presented.trim().as_bytes(), token.as_bytes())) {
+        warn!("rejected admin request");
+        return write_response(stream, "401 Unauthorized", "Unauthorized", "text/plain", "WWW-Authenticate: Bearer\r\n");
+    }
+
+    match path {
+        "/admin/connections" => {
+            // 接続数と接続ごとのキューの状態
+            let hub = hub.lock().unwrap();
+            let stats = format!(
+                r#"{{"connections":{},"history":{},{}}}"#,
+                ACTIVE_STREAMS.load(Ordering::SeqCst),
+                hub.history_len(),
+                hub.queue_stats_json()
+            );
+            send_response(stream, &stats, "application/json")
+        }
+        "/admin/announce" => {
+            // 本文をお知らせとして全クライアントへ流す
+            let body = request.split_once("\r\n\r\n").map_or("", |(_, body)| body);
+            if body.is_empty() {
+                return send_status(stream, "400 Bad Request", "Empty announcement");
+            }
+            hub.lock().unwrap().broadcast(EventKind::Announcement, body);
+            info!("admin announcement sent");
+            send_response(stream, "OK", "text/plain")
+        }
+        _ => send_status(stream, "404 Not Found", "Not found"),
+    }
+}
+
+// トークン比較で一致した長さが時間から漏れないようにする
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0u8, |diff, (x, y)| diff | (x ^ y)) == 0
+}
+
+// 副作用のない GET (と HEAD) の応答を書く
+fn serve_get<W: Write>(
+    out: &mut W,
+    hub: &SharedHub,
+    config: &Config,
+    request: &str,
+    path: &str,
+    query: &str,
+) -> std::io::Result<()> {
+    if path == "/health" {
+        // --- ロードバランサーや Docker 向けのヘルスチェック ---
+        let uptime = STARTED_AT.get().map_or(0, |started| started.elapsed().as_secs());
+        let health = format!(
+            r#"{{"status":"ok","uptime_secs":{},"connections":{},{}}}"#,
+            uptime,
+            ACTIVE_STREAMS.load(Ordering::SeqCst),
+            hub.lock().unwrap().queue_summary_json()
+        );
+        send_response(out, &health, "application/json")
+
+    } else if path == "/history" {
+        // --- 保持中のチャット履歴 (?since=ID より後を、?limit=件数 まで。既定は新規接続時と同じ件数) ---
+        let since = query_param(query, "since").and_then(|id| id.parse().ok()).unwrap_or(0);
+        let limit = query_param(query, "limit").and_then(|limit| limit.parse().ok()).unwrap_or(config.attach_history);
+        let history = hub.lock().unwrap().history_since(since, limit);
+        send_response(out, &events_json(&history), "application/json")
+
     } else {
-        // index.html の提供
-        let path = format!("{}/webroot/index.html", env::current_dir()?.display());
-        let mut file = File::open(path)?;
-        let mut contents = String::new();
-        file.read_to_string(&mut contents)?;
-        send_response(&mut stream, &contents, "text/html")?;
+        // --- 静的ファイル (index.html など) の提供 ---
+        serve_static(out, config, request, path)
+    }
+}
+
+// HEAD 用に、応答のヘッダー部分 (最初の空行まで) だけを通して本文を捨てる
+struct HeadersOnly<W: Write> {
+    inner: W,
+    // "\r\n\r\n" のうち何バイト目まで一致しているか
+    matched: usize,
+}
+
+impl<W: Write> HeadersOnly<W> {
+    fn new(inner: W) -> HeadersOnly<W> {
+        HeadersOnly { inner, matched: 0 }
+    }
+}
+
+impl<W: Write> Write for HeadersOnly<W> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        if self.matched == 4 {
+            return Ok(buf.len());
+        }
+        for (i, &byte) in buf.iter().enumerate() {
+            self.matched = match (self.matched, byte) {
+                (0 | 2, b'\r') | (1 | 3, b'\n') => self.matched + 1,
+                (_, b'\r') => 1,
+                _ => 0,
+            };
+            if self.matched == 4 {
+                self.inner.write_all(&buf[..=i])?;
+                return Ok(buf.len());
+            }
+        }
+        self.inner.write_all(buf)?;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+// SSE でイベントを流し続ける
+fn serve_events(mut stream: TcpStream, hub: SharedHub, config: &Config, request: &str) -> Result<(), failure::Error> {
+    // 再接続してきたブラウザは Last-Event-ID で最後に受け取ったイベントを知らせてくる
+    let last_event_id = header(request, "Last-Event-ID").and_then(|id| id.parse().ok());
+    let (subscription, missed) = hub.lock().unwrap().subscribe(last_event_id);
+
+    // 書き込みが ping 間隔以上詰まったままのクライアントは、スレッドを抱えたままにせず切断する
+    stream.set_write_timeout(Some(config.ping_interval))?;
+    let (mut writer, encoding_headers) = sse_writer(stream.try_clone()?, request);
+    let header = format!(
+        "HTTP/1.1 200 OK\r\n\
+         Content-Type: text/event-stream\r\n\
+         Cache-Control: no-cache\r\n\
+         Connection: keep-alive\r\n\
+         Access-Control-Allow-Origin: *\r\n{}\r\n",
+        encoding_headers
+    );
+    stream.write_all(header.as_bytes())?;
+    Span::current().record("status", 200);
+    info!("SSE stream opened");
+
+    // フレームごとにフラッシュして、圧縮中でもすぐにクライアントへ届ける
+    let mut write_frame = |frame: &str| writer.write_all(frame.as_bytes()).and_then(|_| writer.flush());
+    let mut alive = missed.iter().all(|event| write_frame(&sse_frame(event)).is_ok());
+
+    // チャンネルからメッセージが来るのを待機し、ストリームに流し続ける
+    // 静かな間もプロキシやNATに切られないよう、一定間隔で ping コメントを送る
+    // 待機の合間にソケットを覗いて、クライアントが閉じたことを次の書き込みより先に検知する
+    let tick = config.ping_interval.min(Duration::from_secs(1));
+    stream.set_read_timeout(Some(Duration::from_millis(1)))?;
+    let mut last_write = Instant::now();
+    while alive {
+        let frame = match subscription.recv_timeout(tick) {
+            Ok(event) => sse_frame(&event),
+            Err(mpsc::RecvTimeoutError::Timeout) => {
+                if peer_closed(&stream) {
+                    break;
+                }
+                if last_write.elapsed() < config.ping_interval {
+                    continue;
+                }
+                ": ping\n\n".to_string()
+            }
+            Err(mpsc::RecvTimeoutError::Disconnected) => break,
+        };
+        // クライアントが切断したらループを抜ける
+        alive = write_frame(&frame).is_ok();
+        last_write = Instant::now();
+    }
+    // 次のブロードキャストを待たずに自分の送信元を取り除く
+    hub.lock().unwrap().unsubscribe(subscription.client_id);
+    info!("SSE Connection closed.");
+    Ok(())
+}
+
+// since より新しいイベントがあればすぐ返し、なければ届くかタイムアウトするまで待つ
+fn serve_poll(mut stream: TcpStream, hub: SharedHub, config: &Config, since: u64) -> Result<(), failure::Error> {
+    let (subscription, mut events) = hub.lock().unwrap().subscribe(Some(since));
+    if events.is_empty() {
+        if let Ok(event) = subscription.recv_timeout(config.poll_timeout) {
+            events.push(event);
+            events.extend(subscription.try_iter());
+        }
+    }
+    hub.lock().unwrap().unsubscribe(subscription.client_id);
+    send_response(&mut stream, &events_json(&events), "application/json")?;
+    Ok(())
+}
+
+// ヘッダーと Content-Length 分の本文を読み込む
+// 遅すぎる・大きすぎる・読めないリクエストにはエラーを返して None (切断された場合も None)
+fn read_request(stream: &mut TcpStream, config: &Config) -> Result<Option<String>, failure::Error> {
+    // 1バイトずつ送ってくるような遅いクライアントも、全体の期限で打ち切る
+    let deadline = Instant::now() + config.read_timeout;
+    // 応答を読まないクライアントがワーカーを塞がないよう、書き込みも同じ時間で打ち切る
+    // (SSE は serve_events で ping 間隔に設定し直す)
+    stream.set_write_timeout(Some(config.read_timeout))?;
+    let mut buffer = Vec::with_capacity(1024);
+    let mut chunk = [0u8; 1024];
+
+    let header_end = loop {
+        let end = buffer.windows(4).position(|window| window == b"\r\n\r\n").map(|pos| pos + 4);
+        if end.unwrap_or(buffer.len()) > config.max_header_bytes {
+            send_status(stream, "431 Request Header Fields Too Large", "Request header too large")?;
+            return Ok(None);
+        }
+        if let Some(end) = end {
+            break end;
+        }
+        match read_until(stream, &mut chunk, deadline)? {
+            Some(0) => return Ok(None),
+            Some(nbytes) => buffer.extend_from_slice(&chunk[..nbytes]),
+            None => {
+                send_status(stream, "408 Request Timeout", "Request timeout")?;
+                return Ok(None);
+            }
+        }
+    };
+
+    let head = match str::from_utf8(&buffer[..header_end]) {
+        Ok(head) => head,
+        Err(_) => {
+            send_status(stream, "400 Bad Request", "Request header is not UTF-8")?;
+            return Ok(None);
+        }
+    };
+    // chunked などの本文は読めないので、Content-Length を付けて送り直してもらう
+    if header(head, "Transfer-Encoding").is_some() {
+        send_status(stream, "411 Length Required", "Content-Length required")?;
+        return Ok(None);
+    }
+    let content_length = match header(head, "Content-Length").map(str::parse::<usize>) {
+        Some(Ok(length)) => length,
+        Some(Err(_)) => {
+            send_status(stream, "400 Bad Request", "Invalid Content-Length")?;
+            return Ok(None);
+        }
+        None => 0,
+    };
+    if content_length > config.max_body_bytes {
+        send_status(stream, "413 Payload Too Large", "Request body too large")?;
+        return Ok(None);
+    }
+    while buffer.len() < header_end + content_length {
+        match read_until(stream, &mut chunk, deadline)? {
+            Some(0) => return Ok(None),
+            Some(nbytes) => buffer.extend_from_slice(&chunk[..nbytes]),
+            None => {
+                send_status(stream, "408 Request Timeout", "Request timeout")?;
+                return Ok(None);
+            }
+        }
+    }
+    buffer.truncate(header_end + content_length);
+
+    // 以降は書き込みだけなので読み込みのタイムアウトを外しておく
+    stream.set_read_timeout(None)?;
+    match String::from_utf8(buffer) {
+        Ok(request) => Ok(Some(request)),
+        Err(_) => {
+            send_status(stream, "400 Bad Request", "Request body is not UTF-8")?;
+            Ok(None)
+        }
+    }
+}
+
+// 期限までに読めた分を返す (期限切れなら None)
+fn read_until(stream: &mut TcpStream, buf: &mut [u8], deadline: Instant) -> std::io::Result<Option<usize>> {
+    let remaining = deadline.saturating_duration_since(Instant::now());
+    if remaining.is_zero() {
+        return Ok(None);
+    }
+    stream.set_read_timeout(Some(remaining))?;
+    match stream.read(buf) {
+        Ok(nbytes) => Ok(Some(nbytes)),
+        Err(error) if matches!(error.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut) => Ok(None),
+        Err(error) => Err(error),
+    }
+}
+
+// SSE やロングポーリングのような長時間の接続は、ワーカーを塞がないよう専用スレッドで処理する
+// 上限を超えたら 503 を返して断る
+fn spawn_long_lived<F>(mut stream: TcpStream, config: &Config, serve: F) -> std::io::Result<()>
+where
+    F: FnOnce(TcpStream) -> Result<(), failure::Error> + Send + 'static,
+{
+    if ACTIVE_STREAMS.fetch_add(1, Ordering::SeqCst) >= config.max_streams {
+        ACTIVE_STREAMS.fetch_sub(1, Ordering::SeqCst);
+        return send_status(&mut stream, "503 Service Unavailable", "Too many connections");
     }
+    // 専用スレッドでも元のリクエストの span の中でログを出す
+    let span = Span::current();
+    thread::spawn(move || {
+        let _enter = span.enter();
+        serve(stream).unwrap_or_else(|error| error!("{:?}", error));
+        ACTIVE_STREAMS.fetch_sub(1, Ordering::SeqCst);
+    });
     Ok(())
 }
 
-fn send_response(stream: &mut TcpStream, content: &str, content_type: &str) -> std::io::Result<()> {
+// バケットからトークンを1つ取る。足りなければ再試行までの秒数を返す
+fn take_token(class: RateClass, client: IpAddr, config: &Config) -> Result<(), u64> {
+    let limit = config.rate_limit(class);
+    if limit.per_sec == 0.0 {
+        return Ok(());
+    }
+    let now = Instant::now();
+    let mut table = RATE_BUCKETS.lock().unwrap();
+    // 満タンに戻ったバケットは定期的に捨てて、表が際限なく大きくならないようにする
+    // 戻り具合はバケットごとに、そのエンドポイントの種類の設定で計算する
+    if now.duration_since(table.pruned) >= RATE_PRUNE_INTERVAL {
+        table.buckets.retain(|(class, _), bucket| {
+            let limit = config.rate_limit(*class);
+            bucket.tokens + now.duration_since(bucket.updated).as_secs_f64() * limit.per_sec < limit.burst
+        });
+        table.pruned = now;
+    }
+    let bucket = table
+        .buckets
+        .entry((class, rate_key(client)))
+        .or_insert(Bucket { tokens: limit.burst, updated: now });
+    let elapsed = now.duration_since(bucket.updated).as_secs_f64();
+    bucket.tokens = (bucket.tokens + elapsed * limit.per_sec).min(limit.burst);
+    bucket.updated = now;
+    if bucket.tokens >= 1.0 {
+        bucket.tokens -= 1.0;
+        Ok(())
+    } else {
+        Err(((1.0 - bucket.tokens) / limit.per_sec).ceil() as u64)
+    }
+}
+
+// レート制限で同じクライアントとみなす単位
+// IPv6 は1台に /64 ごと割り当てられることが多いので、/64 でまとめる
+fn rate_key(client: IpAddr) -> IpAddr {
+    match client.to_canonical() {
+        IpAddr::V6(v6) => {
+            let [a, b, c, d, ..] = v6.segments();
+            IpAddr::V6(Ipv6Addr::new(a, b, c, d, 0, 0, 0, 0))
+        }
+        v4 => v4,
+    }
+}
+
+// クライアントがソケットを閉じたか (読み込みのタイムアウトは呼び出し側で短くしておく)
+fn peer_closed(stream: &TcpStream) -> bool {
+    match stream.peek(&mut [0u8; 1]) {
+        Ok(0) => true,
+        Ok(_) => false,
+        Err(error) => !matches!(error.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut),
+    }
+}
+
+// SSE の出力先を用意する
+// クライアントが gzip を受け付けるなら、フレームごとに同期フラッシュする圧縮ライターで包む
+#[cfg(feature = "sse-gzip")]
+fn sse_writer(stream: TcpStream, request: &str) -> (Box<dyn Write>, &'static str) {
+    if accepts_gzip(request) {
+        let encoder = GzEncoder::new(stream, Compression::fast());
+        return (Box::new(encoder), "Content-Encoding: gzip\r\nVary: Accept-Encoding\r\n");
+    }
+    (Box::new(stream), "Vary: Accept-Encoding\r\n")
+}
+
+#[cfg(not(feature = "sse-gzip"))]
+fn sse_writer(stream: TcpStream, _request: &str) -> (Box<dyn Write>, &'static str) {
+    (Box::new(stream), "")
+}
+
+// Accept-Encoding に q=0 以外の gzip が含まれているか
+#[cfg(feature = "sse-gzip")]
+fn accepts_gzip(request: &str) -> bool {
+    header(request, "Accept-Encoding").is_some_and(|value| {
+        value.split(',').any(|coding| {
+            let mut params = coding.split(';').map(str::trim);
+            let name = params.next().unwrap_or("");
+            let rejected = params.any(|param| {
+                param.strip_prefix("q=").and_then(|q| q.parse::<f32>().ok()) == Some(0.0)
+            });
+            name.eq_ignore_ascii_case("gzip") && !rejected
+        })
+    })
+}
+
+// イベントの列を JSON 配列にする
+fn events_json(events: &[ChatEvent]) -> String {
+    let items: Vec<String> = events.iter().map(ChatEvent::to_json).collect();
+    format!("[{}]", items.join(","))
+}
+
+// SSEのフォーマットは "id: 連番\ndata: JSON\n\n"
+// (JSON は改行をエスケープするので data 行は常に1行になる)
+fn sse_frame(event: &ChatEvent) -> String {
+    format!("id: {}\ndata: {}\n\n", event.id, event.to_json())
+}
+
+// 文字列を JSON の文字列リテラルにする
+fn json_string(value: &str) -> String {
+    let mut out = String::with_capacity(value.len() + 2);
+    out.push('"');
+    for c in value.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            c if (c as u32) < 0x20 => out.push_str(&format!("\\u{:04x}", c as u32)),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    out
+}
+
+// webroot 配下のファイルを返す
+// 内容から作った ETag が If-None-Match と一致すれば 304 で本文を省く
+fn serve_static<W: Write>(stream: &mut W, config: &Config, request: &str, path: &str) -> std::io::Result<()> {
+    let relative = asset_path(path);
+    let contents = match load_asset(config, relative)? {
+        Some(contents) => contents,
+        None => return send_status(stream, "404 Not Found", "Not found"),
+    };
+
+    let mut hasher = DefaultHasher::new();
+    contents.hash(&mut hasher);
+    let etag = format!("\"{:016x}\"", hasher.finish());
+    let cache_headers = format!("ETag: {}\r\nCache-Control: no-cache\r\n", etag);
+    let not_modified = header(request, "If-None-Match")
+        .is_some_and(|tags| tags.split(',').any(|tag| tag.trim() == etag || tag.trim() == "*"));
+    if not_modified {
+        return send_not_modified(stream, &cache_headers);
+    }
+    write_raw_response(stream, "200 OK", &contents, mime_type(relative), &cache_headers)
+}
+
+// リクエストのパスを webroot からの相対パスにする ("/" は index.html)
+fn asset_path(path: &str) -> &str {
+    match path.trim_start_matches('/') {
+        "" => "index.html",
+        relative => relative,
+    }
+}
+
+// 外部の webroot が設定されていればディスクから読み、なければ埋め込み版を返す
+// webroot の外を指すパス (.. や隠しファイル、外へのシンボリックリンク) は存在しない扱いにする
+fn load_asset(config: &Config, relative: &str) -> std::io::Result<Option<Cow<'static, [u8]>>> {
+    if relative.contains('\\') || relative.split('/').any(|segment| segment.is_empty() || segment.starts_with('.')) {
+        return Ok(None);
+    }
+    let webroot = match &config.webroot {
+        Some(dir) => dir.clone(),
+        None if cfg!(feature = "embed-webroot") => return Ok(embedded_asset(relative).map(Cow::Borrowed)),
+        None => env::current_dir()?.join("webroot"),
+    };
+    let root = match webroot.canonicalize() {
+        Ok(root) => root,
+        Err(error) => {
+            warn!("webroot {} is unavailable: {}", webroot.display(), error);
+            return Ok(None);
+        }
+    };
+    let file = match root.join(relative).canonicalize() {
+        Ok(file) if file.starts_with(&root) && file.is_file() => file,
+        _ => return Ok(None),
+    };
+    fs::read(file).map(|contents| Some(Cow::Owned(contents)))
+}
+
+#[cfg(feature = "embed-webroot")]
+fn embedded_asset(relative: &str) -> Option<&'static [u8]> {
+    match relative {
+        "index.html" => Some(EMBEDDED_INDEX),
+        _ => None,
+    }
+}
+
+#[cfg(not(feature = "embed-webroot"))]
+fn embedded_asset(_relative: &str) -> Option<&'static [u8]> {
+    None
+}
+
+// 拡張子から Content-Type を決める
+fn mime_type(relative: &str) -> &'static str {
+    let extension = Path::new(relative)
+        .extension()
+        .and_then(|extension| extension.to_str())
+        .unwrap_or("")
+        .to_ascii_lowercase();
+    match extension.as_str() {
+        "html" | "htm" => "text/html;charset=utf-8",
+        "js" | "mjs" => "text/javascript;charset=utf-8",
+        "css" => "text/css;charset=utf-8",
+        "json" => "application/json;charset=utf-8",
+        "txt" => "text/plain;charset=utf-8",
+        "svg" => "image/svg+xml",
+        "png" => "image/png",
+        "jpg" | "jpeg" => "image/jpeg",
+        "gif" => "image/gif",
+        "webp" => "image/webp",
+        "ico" => "image/x-icon",
+        "woff2" => "font/woff2",
+        _ => "application/octet-stream",
+    }
+}
+
+// クエリ文字列から値を取り出す
+fn query_param<'a>(query: &'a str, name: &str) -> Option<&'a str> {
+    query
+        .split('&')
+        .filter_map(|pair| pair.split_once('='))
+        .find(|(key, _)| *key == name)
+        .map(|(_, value)| value)
+}
+
+// リクエストヘッダーの値を取り出す (名前の大文字小文字は区別しない)
+fn header<'a>(request: &'a str, name: &str) -> Option<&'a str> {
+    header_values(request, name).next()
+}
+
+// 同じ名前のヘッダーが複数行あれば、そのすべての値を順に返す
+fn header_values<'a: 'n, 'n>(request: &'a str, name: &'n str) -> impl Iterator<Item = &'a str> + 'n {
+    request
+        .split("\r\n\r\n")
+        .next()
+        .unwrap_or("")
+        .lines()
+        .skip(1)
+        .filter_map(|line| line.split_once(':'))
+        .filter(move |(key, _)| key.trim().eq_ignore_ascii_case(name))
+        .map(|(_, value)| value.trim())
+}
+
+// 実際のクライアントIPを求める
+// 信用するプロキシから来た場合だけ設定した転送ヘッダーをたどり、
+// 右端 (自分に近い側) から見て最初の信用できないアドレスをクライアントとみなす
+fn client_ip(peer: IpAddr, request: &str, trusted: &[IpAddr], source: ForwardedHeader) -> IpAddr {
+    if !trusted.contains(&peer) {
+        return peer;
+    }
+    // 複数行に分かれていても、順につなげた1つのリストとして扱う
+    let values = header_values(request, source.name());
+    let chain: Vec<IpAddr> = match source {
+        ForwardedHeader::Forwarded => values.flat_map(forwarded_for_chain).collect(),
+        ForwardedHeader::XForwardedFor | ForwardedHeader::XRealIp => {
+            values.flat_map(|value| value.split(',')).filter_map(parse_node).collect()
+        }
+    };
+    chain
+        .iter()
+        .rev()
+        .find(|ip| !trusted.contains(ip))
+        .or_else(|| chain.first())
+        .copied()
+        .unwrap_or(peer)
+}
+
+// RFC 7239 の Forwarded ヘッダーから for= の値を順に取り出す
+fn forwarded_for_chain(value: &str) -> Vec<IpAddr> {
+    value
+        .split(',')
+        .flat_map(|element| element.split(';'))
+        .filter_map(|pair| pair.split_once('='))
+        .filter(|(key, _)| key.trim().eq_ignore_ascii_case("for"))
+        .filter_map(|(_, node)| parse_node(node))
+        .collect()
+}
+
+// "192.0.2.1", "192.0.2.1:8080", "\"[2001:db8::1]:4711\"" のようなノード表記を解釈する
+// ("unknown" や難読化された識別子は None)
+fn parse_node(node: &str) -> Option<IpAddr> {
+    let node = node.trim().trim_matches('"');
+    node.parse::<IpAddr>()
+        .ok()
+        .or_else(|| node.parse::<SocketAddr>().ok().map(|addr| addr.ip()))
+        .or_else(|| node.strip_prefix('[')?.strip_suffix(']')?.parse().ok())
+}
+
+// クライアントが使ったスキーム (信用するプロキシ経由なら X-Forwarded-Proto を使う)
+fn forwarded_proto<'a>(peer: IpAddr, request: &'a str, trusted: &[IpAddr]) -> &'a str {
+    if !trusted.contains(&peer) {
+        return "http";
+    }
+    header(request, "X-Forwarded-Proto").unwrap_or("http")
+}
+
+fn send_redirect<W: Write>(stream: &mut W, location: &str) -> std::io::Result<()> {
+    Span::current().record("status", 301);
     let response = format!(
-        "HTTP/1.1 200 OK\r\nContent-Type: {};charset=utf-8\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
-        content_type, content.len(), content
+        "HTTP/1.1 301 Moved Permanently\r\nLocation: {}\r\nContent-Length: 0\r\nConnection: close\r\n\r\n",
+        location
     );
     stream.write_all(response.as_bytes())?;
     stream.flush()
-}
\ No newline at end of file
+}
+
+fn send_response<W: Write>(stream: &mut W, content: &str, content_type: &str) -> std::io::Result<()> {
+    write_response(stream, "200 OK", content, content_type, "")
+}
+
+// エラーなどをプレーンテキストで返す
+fn send_status<W: Write>(stream: &mut W, status: &str, message: &str) -> std::io::Result<()> {
+    write_response(stream, status, message, "text/plain", "")
+}
+
+fn send_too_many_requests<W: Write>(stream: &mut W, retry_after: u64) -> std::io::Result<()> {
+    let retry_header = format!("Retry-After: {}\r\n", retry_after);
+    write_response(stream, "429 Too Many Requests", "Too many requests", "text/plain", &retry_header)
+}
+
+fn write_response<W: Write>(
+    stream: &mut W,
+    status: &str,
+    content: &str,
+    content_type: &str,
+    extra_headers: &str,
+) -> std::io::Result<()> {
+    let content_type = format!("{};charset=utf-8", content_type);
+    write_raw_response(stream, status, content.as_bytes(), &content_type, extra_headers)
+}
+
+// extra_headers は "Name: value\r\n" を並べたもの
+fn write_raw_response<W: Write>(
+    stream: &mut W,
+    status: &str,
+    body: &[u8],
+    content_type: &str,
+    extra_headers: &str,
+) -> std::io::Result<()> {
+    record_status(status);
+    let head = format!(
+        "HTTP/1.1 {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n{}\r\n",
+        status, content_type, body.len(), extra_headers
+    );
+    stream.write_all(head.as_bytes())?;
+    stream.write_all(body)?;
+    stream.flush()
+}
+
+fn send_method_not_allowed<W: Write>(stream: &mut W, allow: &str) -> std::io::Result<()> {
+    let allow_header = format!("Allow: {}\r\n", allow);
+    write_response(stream, "405 Method Not Allowed", "Method not allowed", "text/plain", &allow_header)
+}
+
+fn send_not_modified<W: Write>(stream: &mut W, extra_headers: &str) -> std::io::Result<()> {
+    record_status("304 Not Modified");
+    let head = format!("HTTP/1.1 304 Not Modified\r\n{}Connection: close\r\n\r\n", extra_headers);
+    stream.write_all(head.as_bytes())?;
+    stream.flush()
+}
+
+// アクセスログ用にステータスコードを span に記録する
+fn record_status(status: &str) {
+    if let Some(code) = status.split(' ').next().and_then(|code| code.parse::<u16>().ok()) {
+        Span::current().record("status", code);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::{BufRead, BufReader};
+
+    const PROXY: &str = "10.0.0.1";
+
+    fn ip(value: &str) -> IpAddr {
+        value.parse().unwrap()
+    }
+
+    fn request_with(headers: &str) -> String {
+        format!("GET /send HTTP/1.1\r\nHost: chat\r\n{}\r\n", headers)
+    }
+
+    fn hub_with(replay_buffer: usize, attach_history: usize, client_queue: usize) -> ChatHub {
+        let mut config = Config::from_env();
+        config.replay_buffer = replay_buffer;
+        config.attach_history = attach_history;
+        config.client_queue = client_queue;
+        ChatHub::new(&config)
+    }
+
+    fn ids(events: &[ChatEvent]) -> Vec<u64> {
+        events.iter().map(|event| event.id).collect()
+    }
+
+    // webroot/{index.html, a/b.txt, .hidden, link -> ../secret.txt} と webroot の外の secret.txt を作る
+    fn temp_webroot(name: &str) -> (PathBuf, Config) {
+        let base = env::temp_dir().join(format!("chat-test-{}-{}", std::process::id(), name));
+        let root = base.join("webroot");
+        fs::create_dir_all(root.join("a")).unwrap();
+        fs::write(root.join("index.html"), "index").unwrap();
+        fs::write(root.join("a/b.txt"), "nested").unwrap();
+        fs::write(root.join(".hidden"), "hidden").unwrap();
+        fs::write(base.join("secret.txt"), "secret").unwrap();
+        #[cfg(unix)]
+        std::os::unix::fs::symlink(base.join("secret.txt"), root.join("link")).unwrap();
+        let mut config = Config::from_env();
+        config.webroot = Some(root);
+        (base, config)
+    }
+
+    #[test]
+    fn client_ip_ignores_headers_from_untrusted_peer() {
+        let request = request_with("X-Forwarded-For: 1.2.3.4\r\n");
+        let client = client_ip(ip("203.0.113.9"), &request, &[ip(PROXY)], ForwardedHeader::XForwardedFor);
+        assert_eq!(client, ip("203.0.113.9"));
+    }
+
+    #[test]
+    fn client_ip_uses_rightmost_untrusted_forwarded_for() {
+        // クライアントが自分で付けた値の後ろにプロキシが接続元を追記する
+        let request = request_with("X-Forwarded-For: 1.2.3.4, 9.9.9.7\r\n");
+        let client = client_ip(ip(PROXY), &request, &[ip(PROXY)], ForwardedHeader::XForwardedFor);
+        assert_eq!(client, ip("9.9.9.7"));
+    }
+
+    #[test]
+    fn client_ip_skips_trusted_hops() {
+        let trusted = [ip(PROXY), ip("10.0.0.2")];
+        let request = request_with("X-Forwarded-For: 1.2.3.4, 9.9.9.7, 10.0.0.2\r\n");
+        let client = client_ip(ip(PROXY), &request, &trusted, ForwardedHeader::XForwardedFor);
+        assert_eq!(client, ip("9.9.9.7"));
+    }
+
+    #[test]
+    fn client_ip_ignores_other_forwarding_headers() {
+        let request = request_with("X-Forwarded-For: 9.9.9.7\r\nForwarded: for=10.1.0.5\r\nX-Real-IP: 10.1.0.6\r\n");
+        let client = client_ip(ip(PROXY), &request, &[ip(PROXY)], ForwardedHeader::XForwardedFor);
+        assert_eq!(client, ip("9.9.9.7"));
+    }
+
+    #[test]
+    fn client_ip_merges_repeated_header_lines() {
+        let request = request_with("X-Forwarded-For: 1.2.3.4\r\nX-Forwarded-For: 9.9.9.7\r\n");
+        let client = client_ip(ip(PROXY), &request, &[ip(PROXY)], ForwardedHeader::XForwardedFor);
+        assert_eq!(client, ip("9.9.9.7"));
+    }
+
+    #[test]
+    fn client_ip_skips_unparsable_nodes() {
+        let request = request_with("X-Forwarded-For: 9.9.9.7, unknown\r\n");
+        let client = client_ip(ip(PROXY), &request, &[ip(PROXY)], ForwardedHeader::XForwardedFor);
+        assert_eq!(client, ip("9.9.9.7"));
+    }
+
+    #[test]
+    fn client_ip_falls_back_to_peer_without_header() {
+        let request = request_with("Forwarded: for=1.2.3.4\r\n");
+        let client = client_ip(ip(PROXY), &request, &[ip(PROXY)], ForwardedHeader::XForwardedFor);
+        assert_eq!(client, ip(PROXY));
+    }
+
+    #[test]
+    fn client_ip_uses_leftmost_when_every_hop_is_trusted() {
+        let trusted = [ip(PROXY), ip("10.0.0.2")];
+        let request = request_with("X-Forwarded-For: 10.0.0.2, 10.0.0.1\r\n");
+        let client = client_ip(ip(PROXY), &request, &trusted, ForwardedHeader::XForwardedFor);
+        assert_eq!(client, ip("10.0.0.2"));
+    }
+
+    #[test]
+    fn client_ip_reads_forwarded_when_configured() {
+        let request = request_with("Forwarded: for=1.2.3.4\r\nForwarded: for=\"[2001:db8::1]:4711\";proto=https\r\nX-Forwarded-For: 9.9.9.7\r\n");
+        let client = client_ip(ip(PROXY), &request, &[ip(PROXY)], ForwardedHeader::Forwarded);
+        assert_eq!(client, ip("2001:db8::1"));
+    }
+
+    #[test]
+    fn client_ip_reads_real_ip_when_configured() {
+        let request = request_with("X-Real-IP: 9.9.9.7\r\nX-Forwarded-For: 1.2.3.4\r\n");
+        let client = client_ip(ip(PROXY), &request, &[ip(PROXY)], ForwardedHeader::XRealIp);
+        assert_eq!(client, ip("9.9.9.7"));
+    }
+
+    #[test]
+    fn rate_key_groups_ipv6_by_prefix() {
+        assert_eq!(rate_key(ip("2001:db8:1:2:aaaa::1")), ip("2001:db8:1:2::"));
+        assert_eq!(rate_key(ip("2001:db8:1:2:bbbb::9")), ip("2001:db8:1:2::"));
+        assert_eq!(rate_key(ip("::ffff:192.0.2.1")), ip("192.0.2.1"));
+        assert_eq!(rate_key(ip("192.0.2.1")), ip("192.0.2.1"));
+    }
+
+    #[test]
+    fn forwarded_for_chain_reads_every_for_parameter() {
+        let chain = forwarded_for_chain("for=1.2.3.4;proto=http, For=\"[2001:db8::1]\";by=10.0.0.1, for=unknown");
+        assert_eq!(chain, vec![ip("1.2.3.4"), ip("2001:db8::1")]);
+    }
+
+    #[test]
+    fn forwarded_for_chain_ignores_other_parameters() {
+        assert!(forwarded_for_chain("by=10.0.0.1;host=chat;proto=https").is_empty());
+    }
+
+    #[test]
+    fn parse_node_accepts_addresses_with_and_without_ports() {
+        assert_eq!(parse_node(" 192.0.2.1 "), Some(ip("192.0.2.1")));
+        assert_eq!(parse_node("192.0.2.1:8080"), Some(ip("192.0.2.1")));
+        assert_eq!(parse_node("2001:db8::1"), Some(ip("2001:db8::1")));
+        assert_eq!(parse_node("[2001:db8::1]"), Some(ip("2001:db8::1")));
+        assert_eq!(parse_node("\"[2001:db8::1]:4711\""), Some(ip("2001:db8::1")));
+    }
+
+    #[test]
+    fn parse_node_rejects_obfuscated_nodes() {
+        assert_eq!(parse_node("unknown"), None);
+        assert_eq!(parse_node("_hidden"), None);
+        assert_eq!(parse_node(""), None);
+    }
+
+    #[test]
+    fn subscribe_replays_events_after_last_event_id() {
+        let mut hub = hub_with(10, 2, 8);
+        for text in ["a", "b", "c", "d"] {
+            hub.broadcast(EventKind::Chat, text);
+        }
+        let (_subscription, missed) = hub.subscribe(Some(2));
+        assert_eq!(ids(&missed), vec![3, 4]);
+        let (_subscription, missed) = hub.subscribe(Some(4));
+        assert!(missed.is_empty());
+    }
+
+    #[test]
+    fn history_drops_oldest_event_at_limit() {
+        let mut hub = hub_with(3, 10, 8);
+        for _ in 0..5 {
+            hub.broadcast(EventKind::Chat, "x");
+        }
+        assert_eq!(hub.history_len(), 3);
+        let (_subscription, missed) = hub.subscribe(Some(0));
+        assert_eq!(ids(&missed), vec![3, 4, 5]);
+    }
+
+    #[test]
+    fn history_stays_empty_without_replay_buffer() {
+        let mut hub = hub_with(0, 10, 8);
+        hub.broadcast(EventKind::Chat, "a");
+        hub.broadcast(EventKind::Chat, "b");
+        assert_eq!(hub.history_len(), 0);
+        let (subscription, missed) = hub.subscribe(Some(0));
+        assert!(missed.is_empty());
+        // 履歴を持たなくても ID は続きから振られ、購読中のクライアントには届く
+        hub.broadcast(EventKind::Chat, "c");
+        assert_eq!(subscription.recv_timeout(Duration::ZERO).unwrap().id, 3);
+    }
+
+    #[test]
+    fn subscribe_without_last_event_id_attaches_recent_history() {
+        let mut hub = hub_with(10, 2, 8);
+        for text in ["a", "b", "c", "d"] {
+            hub.broadcast(EventKind::Chat, text);
+        }
+        let (_subscription, recent) = hub.subscribe(None);
+        assert_eq!(ids(&recent), vec![3, 4]);
+
+        let mut hub = hub_with(10, 0, 8);
+        hub.broadcast(EventKind::Chat, "a");
+        let (_subscription, recent) = hub.subscribe(None);
+        assert!(recent.is_empty());
+    }
+
+    #[test]
+    fn broadcast_evicts_subscriber_with_full_queue() {
+        let mut hub = hub_with(10, 0, 2);
+        let (slow, _) = hub.subscribe(None);
+        let (fast, _) = hub.subscribe(None);
+        hub.broadcast(EventKind::Chat, "a");
+        hub.broadcast(EventKind::Chat, "b");
+        assert_eq!(fast.try_iter().count(), 2);
+
+        hub.broadcast(EventKind::Chat, "c");
+        assert_eq!(hub.evicted, 1);
+        assert_eq!(hub.senders.len(), 1);
+        assert_eq!(hub.senders[0].client_id, fast.client_id);
+        // 切断されたクライアントもキューに残っていた分は受け取れる
+        assert_eq!(ids(&slow.try_iter().collect::<Vec<_>>()), vec![1, 2]);
+        assert_eq!(fast.recv_timeout(Duration::ZERO).unwrap().id, 3);
+    }
+
+    #[test]
+    fn broadcast_drops_disconnected_subscriber_without_counting_eviction() {
+        let mut hub = hub_with(10, 0, 2);
+        let (subscription, _) = hub.subscribe(None);
+        drop(subscription);
+        hub.broadcast(EventKind::Chat, "a");
+        assert!(hub.senders.is_empty());
+        assert_eq!(hub.evicted, 0);
+    }
+
+    #[test]
+    fn queue_depth_returns_to_zero_after_receiving() {
+        let mut hub = hub_with(10, 0, 4);
+        let (subscription, _) = hub.subscribe(None);
+        for _ in 0..3 {
+            hub.broadcast(EventKind::Chat, "x");
+        }
+        assert_eq!(subscription.depth.load(Ordering::SeqCst), 3);
+        subscription.recv_timeout(Duration::ZERO).unwrap();
+        assert_eq!(subscription.depth.load(Ordering::SeqCst), 2);
+        assert_eq!(subscription.try_iter().count(), 2);
+        assert_eq!(subscription.depth.load(Ordering::SeqCst), 0);
+    }
+
+    #[test]
+    fn load_asset_serves_files_inside_webroot() {
+        let (base, config) = temp_webroot("inside");
+        assert_eq!(load_asset(&config, asset_path("/")).unwrap().as_deref(), Some(&b"index"[..]));
+        assert_eq!(load_asset(&config, "a/b.txt").unwrap().as_deref(), Some(&b"nested"[..]));
+        assert!(load_asset(&config, "missing.txt").unwrap().is_none());
+        fs::remove_dir_all(base).unwrap();
+    }
+
+    #[test]
+    fn load_asset_rejects_paths_outside_webroot() {
+        let (base, config) = temp_webroot("outside");
+        for relative in ["../secret.txt", "a/../../secret.txt", ".hidden", "a//b.txt", "a\\b.txt", "a/", "link"] {
+            assert!(load_asset(&config, relative).unwrap().is_none(), "{} should not be served", relative);
+        }
+        fs::remove_dir_all(base).unwrap();
+    }
+
+    // ループバックの接続へ raw を送って read_request に読ませ、読めた内容とエラー応答のステータス行を返す
+    fn read_raw_request(config: &Config, raw: &[u8]) -> (Option<String>, String) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let mut client = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        client.write_all(raw).unwrap();
+        let (mut server, _) = listener.accept().unwrap();
+        let request = read_request(&mut server, config).unwrap();
+        let mut status = String::new();
+        if request.is_none() {
+            BufReader::new(&client).read_line(&mut status).unwrap();
+        }
+        (request, status.trim_end().to_string())
+    }
+
+    #[test]
+    fn read_request_returns_complete_request() {
+        let config = Config::from_env();
+        let (request, _) = read_raw_request(&config, b"POST /send HTTP/1.1\r\nContent-Length: 2\r\n\r\nhi");
+        assert_eq!(request.as_deref(), Some("POST /send HTTP/1.1\r\nContent-Length: 2\r\n\r\nhi"));
+    }
+
+    #[test]
+    fn read_request_rejects_oversized_header() {
+        let mut config = Config::from_env();
+        config.max_header_bytes = 64;
+        let raw = format!("GET / HTTP/1.1\r\nX-Padding: {}\r\n\r\n", "a".repeat(100));
+        let (request, status) = read_raw_request(&config, raw.as_bytes());
+        assert!(request.is_none());
+        assert_eq!(status, "HTTP/1.1 431 Request Header Fields Too Large");
+    }
+
+    #[test]
+    fn read_request_rejects_oversized_body() {
+        let mut config = Config::from_env();
+        config.max_body_bytes = 16;
+        let (request, status) = read_raw_request(&config, b"POST /send HTTP/1.1\r\nContent-Length: 100\r\n\r\n");
+        assert!(request.is_none());
+        assert_eq!(status, "HTTP/1.1 413 Payload Too Large");
+    }
+
+    #[test]
+    fn read_request_requires_content_length_for_chunked_body() {
+        let config = Config::from_env();
+        let (request, status) =
+            read_raw_request(&config, b"POST /send HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\n2\r\nhi\r\n0\r\n\r\n");
+        assert!(request.is_none());
+        assert_eq!(status, "HTTP/1.1 411 Length Required");
+    }
+
+    #[test]
+    fn read_request_rejects_invalid_content_length() {
+        let config = Config::from_env();
+        let (request, status) = read_raw_request(&config, b"POST /send HTTP/1.1\r\nContent-Length: abc\r\n\r\n");
+        assert!(request.is_none());
+        assert_eq!(status, "HTTP/1.1 400 Bad Request");
+    }
+
+    #[test]
+    fn read_request_times_out_on_short_body() {
+        let mut config = Config::from_env();
+        config.read_timeout = Duration::from_secs(1);
+        let started = Instant::now();
+        let (request, status) = read_raw_request(&config, b"POST /send HTTP/1.1\r\nContent-Length: 10\r\n\r\nabc");
+        assert!(request.is_none());
+        assert_eq!(status, "HTTP/1.1 408 Request Timeout");
+        assert!(started.elapsed() >= Duration::from_secs(1));
+    }
+}